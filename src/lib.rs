@@ -1,7 +1,7 @@
 //! Serialize TokenStream into Rust Source Code while preserving the LineColumn information
 //! provided by TokenStream Span.
 
-use proc_macro2::{Delimiter, LineColumn, TokenStream, TokenTree};
+use proc_macro2::{Delimiter, LineColumn, Literal, Spacing, TokenStream, TokenTree};
 use rustfmt_nightly as rustfmt;
 use std::mem;
 use std::result::Result;
@@ -26,18 +26,264 @@ use std::result::Result;
 /// ```
 pub trait IntoCode {
     fn into_code(self) -> Result<String, rustfmt::result::OperationError>;
+
+    /// Like [`into_code`](IntoCode::into_code), but lets the caller pick the rustfmt
+    /// settings instead of the Edition 2018 / non-recursive / quiet defaults.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::{CodeOptions, IntoCode};
+    /// use rustfmt_nightly::Edition;
+    ///
+    /// let code = "pub fn foo() {\n    let foo = 'a';\n\n    let bar = 'b';\n}\n";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// let options = CodeOptions::new().edition(Edition::Edition2021);
+    /// assert_eq!(stream.into_code_with_options(&options).unwrap(), code);
+    /// ```
+    fn into_code_with_options(
+        self,
+        options: &CodeOptions,
+    ) -> Result<String, rustfmt::result::OperationError>;
+
+    /// Reconstructs the source exactly as recovered from the token stream's spans,
+    /// without running it through rustfmt. This is the span-faithful counterpart to
+    /// [`into_code`](IntoCode::into_code): whatever layout the original tokens carried
+    /// is what comes back out.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::IntoCode;
+    ///
+    /// let code = "pub fn foo(){let foo='a';let bar='b';}";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// assert_eq!(stream.into_code_preserving(), code);
+    /// ```
+    ///
+    /// `///` and `//!` doc comments round-trip too, instead of coming back as the
+    /// `#[doc = "..."]` attributes proc-macro2 desugars them into:
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::IntoCode;
+    ///
+    /// let code = "/// Greets the caller.\npub fn hello() {}";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// assert_eq!(stream.into_code_preserving(), code);
+    /// ```
+    fn into_code_preserving(self) -> String;
+
+    /// Like [`into_code_preserving`](IntoCode::into_code_preserving), but lets the caller
+    /// pick the `indent` policy used for synthesized leading whitespace.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::{Indent, IntoCode};
+    ///
+    /// let code = "fn foo() {\n\tlet x = 1;\n}";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// assert_eq!(stream.into_code_preserving_with_options(Indent::Tabs), code);
+    /// ```
+    ///
+    /// A continuation line that aligns a token past its indentation depth keeps that
+    /// extra alignment as spaces — only the leading `depth` columns become tabs:
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::{Indent, IntoCode};
+    ///
+    /// let code = "fn foo() {\n\tlet x = 1 +\n\t        2;\n}";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// assert_eq!(stream.into_code_preserving_with_options(Indent::Tabs), code);
+    /// ```
+    fn into_code_preserving_with_options(self, indent: Indent) -> String;
+
+    /// Like [`into_code_preserving`](IntoCode::into_code_preserving), but fills the gaps
+    /// between tokens with the matching bytes of `original` instead of synthesized spaces
+    /// and newlines. This recovers comments and other whitespace-only details that
+    /// proc-macro2 otherwise discards.
+    ///
+    /// Returns [`SourceMismatch`] if a token's span names a line or column that
+    /// `original` doesn't contain, which means `original` isn't the source the spans
+    /// were recorded against. A buffer that does contain every position is sliced as
+    /// given; its contents are not compared with the tokens.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::IntoCode;
+    ///
+    /// let code = "fn foo() {\n    // keep me\n    let x = 1;\n}";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// assert_eq!(stream.into_code_with_source(code).unwrap(), code);
+    /// ```
+    ///
+    /// Passing a buffer too short for the spans the stream was parsed with is caught:
+    ///
+    /// ```
+    /// use proc_macro2_whitespace::IntoCode;
+    ///
+    /// let code = "fn foo() {}";
+    /// let stream = code.parse::<proc_macro2::TokenStream>().unwrap();
+    /// assert!(stream.into_code_with_source("short").is_err());
+    /// ```
+    fn into_code_with_source(self, original: &str) -> Result<String, SourceMismatch>;
+
+    /// Like [`into_code_with_source`](IntoCode::into_code_with_source), but lets the
+    /// caller pick the `indent` policy used for a gap whose slice of `original` would
+    /// run backwards. Positions that `original` doesn't contain are reported as
+    /// [`SourceMismatch`] rather than filled with synthesized whitespace.
+    fn into_code_with_source_with_options(
+        self,
+        original: &str,
+        indent: Indent,
+    ) -> Result<String, SourceMismatch>;
 }
 
 trait IntoCodeHelper {
-    fn into_code_with_original_whitespace(self, code: &mut String, cursor: &mut LineColumn);
+    fn into_code_with_original_whitespace(
+        self,
+        code: &mut String,
+        cursor: &mut LineColumn,
+        source: Option<&SourceMap>,
+        indent: Indent,
+        depth: usize,
+    ) -> Result<(), SourceMismatch>;
+}
+
+/// Indentation policy used for synthesized leading whitespace at the start of a line.
+/// Interior alignment between tokens on the same line is always spaces; this only
+/// governs the run of horizontal whitespace immediately after a newline.
+///
+/// `proc_macro2::LineColumn::column` counts literal characters, not tab-expanded
+/// visual width, so there's no tab-stop width to round a column count to. Instead,
+/// [`Indent::Tabs`] uses the enclosing `Group` nesting depth (how many `{`/`(`/`[`
+/// the position is inside) as the indentation depth: the first `depth` characters of
+/// a new-line run are emitted as tabs, and the rest — the part of the run that aligns
+/// a token with something earlier on the previous line rather than marking a deeper
+/// nesting level — are emitted as spaces, the same way [`Indent::Spaces`] always does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    /// Indent with spaces, one per column (the historical behavior).
+    Spaces,
+    /// Indent with one tab per nesting depth, then spaces for any further alignment.
+    Tabs,
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces
+    }
+}
+
+/// A token span's `LineColumn` couldn't be located in the source buffer passed to
+/// [`IntoCode::into_code_with_source`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMismatch {
+    pub line: usize,
+    pub column: usize,
 }
 
-fn rustfmt(code: String) -> Result<String, rustfmt::result::OperationError> {
+impl std::fmt::Display for SourceMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} column {} is not present in the provided source",
+            self.line, self.column
+        )
+    }
+}
+
+impl std::error::Error for SourceMismatch {}
+
+/// Maps `LineColumn` positions to byte offsets in an original source buffer, so that
+/// [`into_code_with_source`](IntoCode::into_code_with_source) can slice out the exact
+/// bytes between two spans.
+struct SourceMap<'a> {
+    original: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    fn new(original: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            original
+                .char_indices()
+                .filter(|&(_, c)| c == '\n')
+                .map(|(i, _)| i + 1),
+        );
+        SourceMap {
+            original,
+            line_starts,
+        }
+    }
+
+    /// Converts a 1-indexed `LineColumn` to a byte offset into `original`, walking chars
+    /// rather than bytes so multi-byte UTF-8 columns line up correctly. Returns `None`
+    /// if `pos` names a line `original` doesn't have, or a column past the end of that
+    /// line — the search never walks into the next line's text.
+    fn byte_index(&self, pos: &LineColumn) -> Option<usize> {
+        let line_start = *self.line_starts.get(pos.line.checked_sub(1)?)?;
+        let line_end = match self.line_starts.get(pos.line) {
+            Some(&next_line_start) => next_line_start,
+            None => self.original.len(),
+        };
+        let line = &self.original[line_start..line_end];
+        match line.char_indices().nth(pos.column) {
+            Some((offset, _)) => Some(line_start + offset),
+            None if pos.column == line.chars().count() => Some(line_end),
+            None => None,
+        }
+    }
+}
+
+/// Settings controlling the rustfmt pass that [`IntoCode::into_code_with_options`] runs
+/// over the reconstructed source.
+#[derive(Debug, Clone)]
+pub struct CodeOptions {
+    edition: rustfmt::Edition,
+    recursive: bool,
+    verbosity: rustfmt::emitter::Verbosity,
+}
+
+impl Default for CodeOptions {
+    fn default() -> Self {
+        CodeOptions {
+            edition: rustfmt::Edition::Edition2018,
+            recursive: false,
+            verbosity: rustfmt::emitter::Verbosity::Quiet,
+        }
+    }
+}
+
+impl CodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn edition(mut self, edition: rustfmt::Edition) -> Self {
+        self.edition = edition;
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: rustfmt::emitter::Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+}
+
+fn rustfmt(code: String, options: &CodeOptions) -> Result<String, rustfmt::result::OperationError> {
     let mut config = rustfmt::Config::default();
-    config.set().edition(rustfmt::Edition::Edition2018);
+    config.set().edition(options.edition);
     let report = rustfmt::format(rustfmt::Input::Text(code), &config, rustfmt::OperationSetting {
-        recursive: false,
-        verbosity: rustfmt::emitter::Verbosity::Quiet,
+        recursive: options.recursive,
+        verbosity: options.verbosity,
     })?;
     let (_, result) = report.format_result().next().unwrap();
     Ok(result.formatted_text().to_owned())
@@ -45,22 +291,76 @@ fn rustfmt(code: String) -> Result<String, rustfmt::result::OperationError> {
 
 impl IntoCode for TokenStream {
     fn into_code(self) -> Result<String, rustfmt::result::OperationError> {
+        self.into_code_with_options(&CodeOptions::default())
+    }
+
+    fn into_code_with_options(
+        self,
+        options: &CodeOptions,
+    ) -> Result<String, rustfmt::result::OperationError> {
+        let mut cursor = LineColumn { line: 1, column: 0 };
+        let mut code = String::new();
+        self.into_code_with_original_whitespace(&mut code, &mut cursor, None, Indent::default(), 0)
+            .expect("fill_whitespace cannot fail without a source buffer");
+        rustfmt(code, options)
+    }
+
+    fn into_code_preserving(self) -> String {
+        self.into_code_preserving_with_options(Indent::default())
+    }
+
+    fn into_code_preserving_with_options(self, indent: Indent) -> String {
         let mut cursor = LineColumn { line: 1, column: 0 };
         let mut code = String::new();
-        self.into_code_with_original_whitespace(&mut code, &mut cursor);
-        rustfmt(code)
+        self.into_code_with_original_whitespace(&mut code, &mut cursor, None, indent, 0)
+            .expect("fill_whitespace cannot fail without a source buffer");
+        code
+    }
+
+    fn into_code_with_source(self, original: &str) -> Result<String, SourceMismatch> {
+        self.into_code_with_source_with_options(original, Indent::default())
+    }
+
+    fn into_code_with_source_with_options(
+        self,
+        original: &str,
+        indent: Indent,
+    ) -> Result<String, SourceMismatch> {
+        let source = SourceMap::new(original);
+        let mut cursor = LineColumn { line: 1, column: 0 };
+        let mut code = String::new();
+        self.into_code_with_original_whitespace(&mut code, &mut cursor, Some(&source), indent, 0)?;
+        Ok(code)
     }
 }
 
 impl IntoCodeHelper for TokenStream {
-    fn into_code_with_original_whitespace(self, code: &mut String, cursor: &mut LineColumn) {
+    fn into_code_with_original_whitespace(
+        self,
+        code: &mut String,
+        cursor: &mut LineColumn,
+        source: Option<&SourceMap>,
+        indent: Indent,
+        depth: usize,
+    ) -> Result<(), SourceMismatch> {
         let mut needs_space = false;
-        for token in self.into_iter() {
+        let tokens: Vec<TokenTree> = self.into_iter().collect();
+        let mut index = 0;
+        while index < tokens.len() {
+            if let Some(consumed) =
+                try_doc_comment(&tokens, index, cursor, code, source, indent, depth)?
+            {
+                index += consumed;
+                needs_space = false;
+                continue;
+            }
+            let token = tokens[index].clone();
+            index += 1;
             let span = token.span();
             match token {
                 TokenTree::Group(group) => {
                     let span = group.span_open();
-                    fill_whitespace(cursor, &span.start(), code);
+                    fill_whitespace(cursor, &span.start(), code, source, indent, depth)?;
                     mem::swap(cursor, &mut {
                         let mut end = span.start();
                         end.column += 1;
@@ -74,9 +374,13 @@ impl IntoCodeHelper for TokenStream {
                         Delimiter::None => 'Ø',
                     };
                     code.push(delim_open);
-                    group
-                        .stream()
-                        .into_code_with_original_whitespace(code, cursor);
+                    group.stream().into_code_with_original_whitespace(
+                        code,
+                        cursor,
+                        source,
+                        indent,
+                        depth + 1,
+                    )?;
                     let span = group.span_close();
                     fill_whitespace(
                         cursor,
@@ -88,7 +392,10 @@ impl IntoCodeHelper for TokenStream {
                             end
                         },
                         code,
-                    );
+                        source,
+                        indent,
+                        depth,
+                    )?;
                     mem::swap(cursor, &mut span.end());
                     let delim_close = match delimiter {
                         Delimiter::Parenthesis => ')',
@@ -112,26 +419,60 @@ impl IntoCodeHelper for TokenStream {
                     } else {
                         needs_space = false;
                     };
-                    fill_whitespace(cursor, start, code);
+                    fill_whitespace(cursor, start, code, source, indent, depth)?;
                     code.push_str(&token.to_string());
                     mem::swap(cursor, &mut span.end());
                 }
             }
         }
+        Ok(())
     }
 }
 
-fn fill_whitespace(prev: &LineColumn, curr: &LineColumn, code: &mut String) {
+fn fill_whitespace(
+    prev: &LineColumn,
+    curr: &LineColumn,
+    code: &mut String,
+    source: Option<&SourceMap>,
+    indent: Indent,
+    depth: usize,
+) -> Result<(), SourceMismatch> {
     if prev.line == 1 && prev.column == 0
         || curr.line == 1 && curr.column == 0
         || prev.line > curr.line
         || (prev.line == curr.line && prev.column > curr.column)
     {
-        return;
+        return Ok(());
+    }
+
+    if let Some(source) = source {
+        match (source.byte_index(prev), source.byte_index(curr)) {
+            (Some(start), Some(end)) if end >= start => {
+                code.push_str(&source.original[start..end]);
+                return Ok(());
+            }
+            // Non-monotonic slice: fall back to the synthesized whitespace below
+            // instead of copying garbage.
+            (Some(_), Some(_)) => {}
+            (None, _) => {
+                return Err(SourceMismatch {
+                    line: prev.line,
+                    column: prev.column,
+                })
+            }
+            (Some(_), None) => {
+                return Err(SourceMismatch {
+                    line: curr.line,
+                    column: curr.column,
+                })
+            }
+        }
     }
+
+    let starts_new_line = curr.line > prev.line;
     let mut whitespace = LineColumn {
         line: curr.line - prev.line,
-        column: if curr.line > prev.line {
+        column: if starts_new_line {
             curr.column
         } else {
             curr.column - prev.column
@@ -141,8 +482,141 @@ fn fill_whitespace(prev: &LineColumn, curr: &LineColumn, code: &mut String) {
         code.push('\n');
         whitespace.line -= 1;
     }
-    while whitespace.column > 0 {
-        code.push(' ');
-        whitespace.column -= 1;
+    if starts_new_line {
+        push_indent(code, whitespace.column, indent, depth);
+    } else {
+        for _ in 0..whitespace.column {
+            code.push(' ');
+        }
+    }
+    Ok(())
+}
+
+/// Pushes `columns` worth of leading horizontal whitespace onto `code`, following
+/// `indent`. `columns` is a `LineColumn` character count, not a tab-expanded visual
+/// width, so it can't tell indentation from same-line alignment by itself — that's
+/// what `depth` (the enclosing `Group` nesting depth) is for: with [`Indent::Tabs`],
+/// the first `depth` columns of the run are genuine indentation and become tabs, one
+/// per nesting level, while any remaining columns are alignment and stay spaces, the
+/// same as [`Indent::Spaces`] treats every column.
+fn push_indent(code: &mut String, columns: usize, indent: Indent, depth: usize) {
+    match indent {
+        Indent::Spaces => {
+            for _ in 0..columns {
+                code.push(' ');
+            }
+        }
+        Indent::Tabs => {
+            let tabs = columns.min(depth);
+            for _ in 0..tabs {
+                code.push('\t');
+            }
+            for _ in 0..(columns - tabs) {
+                code.push(' ');
+            }
+        }
+    }
+}
+
+/// Recognizes the `# [doc = "..."]` / `# ! [doc = "..."]` attribute shape that proc-macro2
+/// desugars `///` and `//!` comments into, and rewrites it back into comment form. Returns
+/// the number of tokens consumed from `tokens[index..]` on a match, or `None` to leave the
+/// tokens for the caller to handle as ordinary tokens.
+fn try_doc_comment(
+    tokens: &[TokenTree],
+    index: usize,
+    cursor: &mut LineColumn,
+    code: &mut String,
+    source: Option<&SourceMap>,
+    indent: Indent,
+    depth: usize,
+) -> Result<Option<usize>, SourceMismatch> {
+    let hash = match tokens.get(index) {
+        Some(TokenTree::Punct(hash)) if hash.as_char() == '#' => hash,
+        _ => return Ok(None),
+    };
+    let mut end = index + 1;
+    let bang = matches!(tokens.get(end), Some(TokenTree::Punct(p)) if p.as_char() == '!');
+    if bang {
+        end += 1;
+    }
+    let group = match tokens.get(end) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+        _ => return Ok(None),
+    };
+    let inner: Vec<TokenTree> = group.stream().into_iter().collect();
+    let content = match doc_attr_content(&inner) {
+        Some(content) => content,
+        None => return Ok(None),
+    };
+
+    fill_whitespace(cursor, &hash.span().start(), code, source, indent, depth)?;
+    let prefix = if bang { "//!" } else { "///" };
+    for (n, line) in content.split('\n').enumerate() {
+        if n > 0 {
+            code.push('\n');
+        }
+        code.push_str(prefix);
+        code.push_str(line);
+    }
+    *cursor = group.span_close().end();
+    Ok(Some(end + 1 - index))
+}
+
+fn doc_attr_content(inner: &[TokenTree]) -> Option<String> {
+    match inner {
+        [TokenTree::Ident(ident), TokenTree::Punct(eq), TokenTree::Literal(literal)]
+            if ident == "doc" && eq.as_char() == '=' && eq.spacing() == Spacing::Alone =>
+        {
+            doc_literal_content(literal)
+        }
+        _ => None,
+    }
+}
+
+/// Recovers the text content of a string literal as rustc's doc-comment desugaring
+/// would have produced it: either a raw string (`r"..."`, `r#"..."#`, ...) or a quoted,
+/// escaped string.
+fn doc_literal_content(literal: &Literal) -> Option<String> {
+    let repr = literal.to_string();
+    if let Some(rest) = repr.strip_prefix('r') {
+        let hashes = rest.chars().take_while(|&c| c == '#').count();
+        let inner = rest[hashes..].strip_prefix('"')?;
+        let closing = format!("\"{}", "#".repeat(hashes));
+        inner.strip_suffix(closing.as_str()).map(str::to_owned)
+    } else {
+        let inner = repr.strip_prefix('"')?.strip_suffix('"')?;
+        Some(unescape_literal(inner))
+    }
+}
+
+fn unescape_literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('\'') => out.push('\''),
+            Some('"') => out.push('"'),
+            Some('u') => {
+                if chars.next() == Some('{') {
+                    let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    if let Some(ch) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        out.push(ch);
+                    }
+                }
+            }
+            Some(other) => out.push(other),
+            None => {}
+        }
     }
+    out
 }